@@ -2,15 +2,65 @@ extern crate libc;
 extern crate memchr;
 
 use memchr::memchr;
-use std::{ffi, mem, ops, ptr, slice};
+use std::borrow::Cow;
+use std::str::Utf8Error;
+use std::marker::PhantomData;
+use std::{cmp, ffi, fmt, hash, mem, ops, ptr, slice, str};
+
+/// A C allocator matching a `malloc`/`free` pair.
+///
+/// The crate's whole guarantee is "allocated and freed by the same matching
+/// allocator". Implementing this trait lets you pick *which* C allocator that
+/// is, for FFI boundaries that hand out (or expect back) pointers owned by a
+/// specific `malloc`/`free` — e.g. a library shipping its own `xmalloc`/`xfree`.
+///
+/// # Safety
+///
+/// `alloc` must return either null or a pointer owned by this allocator, and
+/// `free` must be the exact counterpart for pointers `alloc` returned.
+pub unsafe trait CAllocator {
+    /// Allocate `size` bytes, returning null on failure.
+    ///
+    /// # Safety
+    ///
+    /// Standard allocator contract: the returned pointer (if non-null) is
+    /// valid for `size` bytes and uninitialized.
+    unsafe fn alloc(size: usize) -> *mut u8;
+    /// Free a pointer previously returned by `alloc`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by this allocator's `alloc` and not yet
+    /// freed.
+    unsafe fn free(ptr: *mut u8);
+}
+
+/// The default allocator, backed by the C runtime's `malloc`/`free`.
+pub struct LibcAlloc;
+
+unsafe impl CAllocator for LibcAlloc {
+    #[inline]
+    unsafe fn alloc(size: usize) -> *mut u8 {
+        libc::malloc(size) as *mut u8
+    }
+
+    #[inline]
+    unsafe fn free(ptr: *mut u8) {
+        libc::free(ptr as *mut _)
+    }
+}
 
 /// Rust's CString, but is safe to free
 /// via `free()`. We guarantee this will
 /// always have been allocated via `malloc`,
 /// and always will be freed via `free()`.
-pub struct FreeString {
+///
+/// The allocator is pluggable via the `A` type parameter; it defaults to the
+/// libc `malloc`/`free` pair.
+pub struct FreeString<A: CAllocator = LibcAlloc> {
     inner: *const u8,
     len: usize,
+    _marker: PhantomData<A>,
 }
 
 pub struct NulError(usize);
@@ -20,20 +70,56 @@ pub enum FromBytesWithNulError {
 }
 
 
-impl FreeString {
+impl FreeString<LibcAlloc> {
     /// Construct from a byte buffer. Will return an error if any
     /// byte is null
     pub fn new(bytes: &[u8]) -> Result<Self, NulError> {
+        Self::new_in(bytes)
+    }
+
+    /// Construct from a null terminated byte buffer. Will return an error
+    /// if the last byte is not null, or if any other byte is null.
+    pub fn from_bytes_with_nul(bytes: &[u8])
+                               -> Result<FreeString<LibcAlloc>, FromBytesWithNulError> {
+        Self::from_bytes_with_nul_in(bytes)
+    }
+
+    /// Construct from a buffer that is null terminated somewhere, ignoring
+    /// whatever follows the first null. Useful for reading out of an oversized
+    /// fixed buffer that a C API filled and left garbage in past the nul.
+    ///
+    /// Returns `NotNulTerminated` if there is no null byte at all.
+    pub fn from_bytes_until_nul(bytes: &[u8])
+                                -> Result<FreeString<LibcAlloc>, FromBytesWithNulError> {
+        Self::from_bytes_until_nul_in(bytes)
+    }
+
+    /// Construct a FreeString from a pointer obtained from C
+    ///
+    /// Safety preconditions:
+    ///
+    /// - ptr must be a valid malloc-allocated pointer to a null-terminated C String
+    #[inline]
+    pub unsafe fn from_raw(ptr: *mut libc::c_char) -> Self {
+        Self::from_raw_in(ptr)
+    }
+}
+
+impl<A: CAllocator> FreeString<A> {
+    /// Construct from a byte buffer, allocating with `A`. Will return an error
+    /// if any byte is null.
+    pub fn new_in(bytes: &[u8]) -> Result<Self, NulError> {
         match memchr(0, &bytes) {
             Some(i) => Err(NulError(i)),
             None => Ok(unsafe { Self::from_bytes_unchecked(bytes) }),
         }
     }
 
-    /// Construct from a null terminated byte buffer. Will return an error
-    /// if the last byte is not null, or if any other byte is null.
-    pub fn from_bytes_with_nul(bytes: &[u8])
-                               -> Result<FreeString, FromBytesWithNulError> {
+    /// Construct from a null terminated byte buffer, allocating with `A`. Will
+    /// return an error if the last byte is not null, or if any other byte is
+    /// null.
+    pub fn from_bytes_with_nul_in(bytes: &[u8])
+                                  -> Result<FreeString<A>, FromBytesWithNulError> {
         let nul_pos = memchr::memchr(0, bytes);
         if let Some(nul_pos) = nul_pos {
             if nul_pos + 1 != bytes.len() {
@@ -45,6 +131,22 @@ impl FreeString {
         }
     }
 
+    /// Construct from a buffer that is null terminated somewhere, allocating
+    /// with `A` and ignoring whatever follows the first null. Useful for
+    /// reading out of an oversized fixed buffer that a C API filled and left
+    /// garbage in past the nul.
+    ///
+    /// Returns `NotNulTerminated` if there is no null byte at all.
+    pub fn from_bytes_until_nul_in(bytes: &[u8])
+                                   -> Result<FreeString<A>, FromBytesWithNulError> {
+        match memchr(0, bytes) {
+            Some(nul_pos) => {
+                Ok(unsafe { Self::from_bytes_with_nul_unchecked(&bytes[..=nul_pos]) })
+            }
+            None => Err(FromBytesWithNulError::NotNulTerminated),
+        }
+    }
+
     /// Construct from some bytes which we know contain no null. This
     /// function will append a null terminator whilst constructing.
     ///
@@ -55,7 +157,7 @@ impl FreeString {
         // we turbofish [u8] here to ensure that we don't accidentally
         // size_of_val on &&[u8] or something
         let size = mem::size_of_val::<[u8]>(bytes);
-        let buf = libc::malloc(size + mem::size_of::<u8>()) as *mut u8;
+        let buf = A::alloc(size + mem::size_of::<u8>());
 
         if buf.is_null() {
             panic!("Out of memory")
@@ -86,7 +188,7 @@ impl FreeString {
         // we turbofish [u8] here to ensure that we don't accidentally
         // size_of_val on &&[u8] or something
         let size = mem::size_of_val::<[u8]>(bytes);
-        let buf = libc::malloc(size) as *mut u8;
+        let buf = A::alloc(size);
 
         if buf.is_null() {
             panic!("Out of memory")
@@ -106,13 +208,33 @@ impl FreeString {
         self.inner
     }
 
+    /// Consume the `FreeString` and return its raw pointer, relinquishing
+    /// ownership of the allocation. The caller is now responsible for freeing
+    /// it.
+    ///
+    /// The returned pointer must eventually be released by `A`'s allocator
+    /// (`A::free`) — for the default `LibcAlloc` that is plain C `free()`, so
+    /// the pointer is safe to hand to a C API that will `free()` it, which is
+    /// the whole point of `FreeString`. For a custom `A`, release it through
+    /// that allocator's matching free instead; a mismatched free is UB. To
+    /// reclaim the pointer on the Rust side, pass it back to `from_raw_in`
+    /// (or `from_raw` for the default `LibcAlloc`).
     #[inline]
-    /// Construct a FreeString from a pointer obtained from C
+    pub fn into_raw(self) -> *mut libc::c_char {
+        let ptr = self.inner as *mut libc::c_char;
+        mem::forget(self);
+        ptr
+    }
+
+    #[inline]
+    /// Construct a FreeString from a pointer obtained from C, allocated by
+    /// `A`'s allocator.
     ///
     /// Safety preconditions:
     ///
-    /// - ptr must be a valid malloc-allocated pointer to a null-terminated C String
-    pub unsafe fn from_raw(ptr: *mut libc::c_char) -> Self {
+    /// - ptr must be a valid null-terminated C String allocated by `A`'s
+    ///   allocator (since `Drop` will release it via `A::free`)
+    pub unsafe fn from_raw_in(ptr: *mut libc::c_char) -> Self {
         let len = libc::strlen(ptr) + 1; // Including the NUL byte
         Self::from_raw_parts(ptr as *mut _, len)
     }
@@ -125,9 +247,19 @@ impl FreeString {
     /// - ptr must be a valid malloc-allocated pointer to a null-terminated C String
     /// - len must be the length of that string including the null byte
     pub unsafe fn from_raw_parts(ptr: *const u8, len: usize) -> Self {
+        // Every *_unchecked constructor funnels through here, so this is the
+        // one place we need to catch a caller who handed us a malformed C
+        // string. Cheap to check in debug, compiled out in release.
+        debug_assert!(len > 0, "C string must have at least a null terminator");
+        let slice = slice::from_raw_parts(ptr, len);
+        debug_assert!(slice[len - 1] == 0, "C string must be null terminated");
+        debug_assert!(memchr(0, &slice[..len - 1]).is_none(),
+                      "C string must not contain an interior null");
+
         FreeString {
             inner: ptr,
-            len: len
+            len: len,
+            _marker: PhantomData,
         }
     }
 
@@ -135,15 +267,221 @@ impl FreeString {
     pub fn as_slice(&self) -> &[u8] {
         unsafe { slice::from_raw_parts(self.inner, self.len) }
     }
+
+    /// The string's bytes, *not* including the trailing null terminator.
+    #[inline]
+    pub fn to_bytes(&self) -> &[u8] {
+        let slice = self.as_slice();
+        &slice[..slice.len() - 1]
+    }
+
+    /// The string's bytes, including the trailing null terminator.
+    #[inline]
+    pub fn to_bytes_with_nul(&self) -> &[u8] {
+        self.as_slice()
+    }
+
+    /// Interpret the string as UTF-8, validating the non-null bytes.
+    #[inline]
+    pub fn to_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(self.to_bytes())
+    }
+
+    /// Interpret the string as UTF-8, replacing any invalid sequences with
+    /// the replacement character. Borrows when the bytes are already valid.
+    pub fn to_string_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.to_bytes())
+    }
+}
+
+impl<A: CAllocator> Clone for FreeString<A> {
+    /// Duplicate the string into a fresh allocation, strdup-style. The copy
+    /// owns its own buffer from `A`'s allocator (`malloc` for the default
+    /// `LibcAlloc`), so both it and the original can be freed independently.
+    fn clone(&self) -> Self {
+        unsafe {
+            let buf = A::alloc(self.len);
+            if buf.is_null() {
+                panic!("Out of memory")
+            }
+            ptr::copy_nonoverlapping(self.inner, buf, self.len);
+            Self::from_raw_parts(buf, self.len)
+        }
+    }
+}
+
+impl<A: CAllocator> Drop for FreeString<A> {
+    fn drop(&mut self) {
+        unsafe { A::free(self.inner as *mut u8) }
+    }
+}
+
+/// A growable builder for a `FreeString`.
+///
+/// Unlike `FreeString`, which needs all of its bytes up front, this owns a
+/// `malloc`ed buffer that grows on demand (via `realloc`, doubling the
+/// capacity so appends amortize to O(1)). It implements `fmt::Write`, so you
+/// can assemble a C string incrementally with `write!`, then hand off the
+/// malloc allocation to a `FreeString` with `finish()` — no intermediate
+/// Rust `String`/`Vec` and no final re-copy.
+pub struct FreeStringBuilder {
+    buf: *mut u8,
+    len: usize,
+    capacity: usize,
+}
+
+impl FreeStringBuilder {
+    /// Construct an empty builder. No allocation happens until the first
+    /// byte is pushed.
+    pub fn new() -> Self {
+        FreeStringBuilder {
+            buf: ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    /// Make sure we have room for at least `extra` more bytes, growing the
+    /// malloc buffer with amortized doubling if not.
+    fn reserve(&mut self, extra: usize) {
+        let needed = self.len.checked_add(extra).expect("Overflow while allocating");
+        if needed <= self.capacity {
+            return;
+        }
+
+        // double the capacity until it's large enough, starting from a small
+        // non-zero base so the first push doesn't crawl byte by byte
+        let mut new_cap = if self.capacity == 0 { 8 } else { self.capacity };
+        while new_cap < needed {
+            new_cap = new_cap.checked_mul(2).expect("Overflow while allocating");
+        }
+
+        let buf = unsafe { libc::realloc(self.buf as *mut _, new_cap) as *mut u8 };
+        if buf.is_null() {
+            panic!("Out of memory")
+        }
+        self.buf = buf;
+        self.capacity = new_cap;
+    }
+
+    /// Append raw bytes to the builder.
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        // Nothing to copy, and `self.buf` may still be null on a fresh
+        // builder — don't hand a null pointer to copy_nonoverlapping.
+        if bytes.is_empty() {
+            return;
+        }
+        self.reserve(bytes.len());
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), self.buf.add(self.len), bytes.len());
+        }
+        self.len += bytes.len();
+    }
+
+    /// Finish building, yielding a `FreeString`. Runs `memchr` once over the
+    /// accumulated bytes to reject any interior null, then appends a null
+    /// terminator in place (reusing spare capacity or doing one final
+    /// `realloc`) and transfers the malloc allocation without copying.
+    pub fn finish(mut self) -> Result<FreeString, NulError> {
+        if let Some(i) = memchr(0, self.as_slice()) {
+            return Err(NulError(i));
+        }
+
+        // make room for the terminator and write it in place
+        self.reserve(1);
+        unsafe {
+            *self.buf.add(self.len) = 0;
+        }
+        let total_len = self.len + 1;
+
+        // hand the allocation off untouched; forget ourselves so Drop doesn't
+        // free it out from under the FreeString
+        let buf = self.buf;
+        mem::forget(self);
+        Ok(unsafe { FreeString::from_raw_parts(buf, total_len) })
+    }
+
+    /// The bytes accumulated so far, excluding any terminator (there isn't one
+    /// until `finish`).
+    fn as_slice(&self) -> &[u8] {
+        if self.buf.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.buf, self.len) }
+        }
+    }
+}
+
+impl Default for FreeStringBuilder {
+    fn default() -> Self {
+        FreeStringBuilder::new()
+    }
+}
+
+impl fmt::Write for FreeStringBuilder {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_bytes(s.as_bytes());
+        Ok(())
+    }
 }
 
-impl Drop for FreeString {
+impl Drop for FreeStringBuilder {
     fn drop(&mut self) {
-        unsafe { libc::free(self.inner as *mut u8 as *mut _) }
+        if !self.buf.is_null() {
+            unsafe { libc::free(self.buf as *mut _) }
+        }
+    }
+}
+
+impl<A: CAllocator> PartialEq for FreeString<A> {
+    fn eq(&self, other: &FreeString<A>) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<A: CAllocator> Eq for FreeString<A> {}
+
+impl<A: CAllocator> PartialOrd for FreeString<A> {
+    fn partial_cmp(&self, other: &FreeString<A>) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<A: CAllocator> Ord for FreeString<A> {
+    fn cmp(&self, other: &FreeString<A>) -> cmp::Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl<A: CAllocator> hash::Hash for FreeString<A> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl<A: CAllocator> fmt::Display for FreeString<A> {
+    /// Render the bytes up to (but not including) the null terminator,
+    /// emitting printable ASCII directly and escaping everything else as
+    /// `\xNN`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in self.to_bytes() {
+            if (0x20..0x7f).contains(&b) {
+                (b as char).fmt(f)?;
+            } else {
+                write!(f, "\\x{:02x}", b)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<A: CAllocator> fmt::Debug for FreeString<A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self)
     }
 }
 
-impl ops::Deref for FreeString {
+impl<A: CAllocator> ops::Deref for FreeString<A> {
     type Target = ffi::CStr;    
     // the lifetime here isn't necessary, but it's
     // helpful to be clear here. from_bytes_with_nul_unchecked